@@ -28,14 +28,22 @@ const ENV_CONFIG_BASE64: &str = "ATTIC_SERVER_CONFIG_BASE64";
 /// Environment variable storing the Base64-encoded HS256 JWT secret.
 const ENV_TOKEN_HS256_SECRET_BASE64: &str = "ATTIC_SERVER_TOKEN_HS256_SECRET_BASE64";
 
+/// Environment variable storing the path to a file containing the Base64-encoded HS256 JWT secret.
+///
+/// If set, this takes precedence over `token-hs256-secret-file`.
+const ENV_TOKEN_HS256_SECRET_FILE: &str = "ATTIC_SERVER_TOKEN_HS256_SECRET_FILE";
+
+/// Environment variable overriding `allow-world-readable-secrets`.
+const ENV_ALLOW_WORLD_READABLE_SECRETS: &str = "ATTIC_SERVER_ALLOW_WORLD_READABLE_SECRETS";
+
 /// Configuration for the Attic Server.
 #[derive(Clone, Derivative, Deserialize)]
 #[derivative(Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
-    /// Socket address to listen on.
+    /// Address to listen on.
     #[serde(default = "default_listen_address")]
-    pub listen: SocketAddr,
+    pub listen: ListenAddr,
 
     /// Allowed `Host` headers.
     ///
@@ -101,14 +109,112 @@ pub struct Config {
     #[serde(default = "Default::default")]
     pub garbage_collection: GarbageCollectionConfig,
 
+    /// Background data scrubber.
+    #[serde(default = "Default::default")]
+    pub scrub: ScrubConfig,
+
     /// JSON Web Token HMAC secret.
     ///
     /// Set this to the base64 encoding of a randomly generated secret.
+    ///
+    /// Overridden by `ATTIC_SERVER_TOKEN_HS256_SECRET_BASE64` if that is set,
+    /// and by `token-hs256-secret-file` (or its environment variable). Use
+    /// `resolve_token_hs256_secret` to get the secret that actually applies.
     #[serde(rename = "token-hs256-secret-base64")]
     #[serde(deserialize_with = "deserialize_token_hs256_secret_base64")]
-    #[serde(default = "load_token_hs256_secret_from_env")]
+    #[serde(default)]
+    #[derivative(Debug = "ignore")]
+    pub token_hs256_secret_base64: Option<HS256Key>,
+
+    /// Path to a file containing the Base64-encoded HS256 JWT secret.
+    ///
+    /// If set (or if `ATTIC_SERVER_TOKEN_HS256_SECRET_FILE` is set), this
+    /// takes precedence over `token-hs256-secret-base64`. This allows the
+    /// secret to be managed by systemd credentials, Kubernetes secret
+    /// mounts, or sops-nix without ending up in the TOML configuration.
+    ///
+    /// The file must not be readable by group or others unless
+    /// `allow-world-readable-secrets` is set.
+    #[serde(rename = "token-hs256-secret-file")]
+    #[serde(default)]
     #[derivative(Debug = "ignore")]
-    pub token_hs256_secret: HS256Key,
+    pub token_hs256_secret_file: Option<PathBuf>,
+
+    /// Whether to allow secret files that are readable by group or others.
+    ///
+    /// By default, `atticd` refuses to start if `token-hs256-secret-file`
+    /// points to a file that is readable by anyone other than its owner.
+    /// Set this to `true` to disable the check in environments where
+    /// tightening the permissions is impractical (e.g., restrictive ACLs
+    /// or read-only mounts). Can also be set via
+    /// `ATTIC_SERVER_ALLOW_WORLD_READABLE_SECRETS`.
+    #[serde(rename = "allow-world-readable-secrets")]
+    #[serde(default)]
+    pub allow_world_readable_secrets: bool,
+}
+
+/// Address to listen on.
+///
+/// This can either be a TCP socket address (`host:port`) or, prefixed with
+/// `unix:`, the path to a Unix domain socket (`unix:/path/to/socket`). The
+/// latter lets `atticd` run behind a reverse proxy, or be socket-activated
+/// by systemd, without exposing a TCP port. Modeled on Garage's
+/// `UnixOrTCPSocketAddress`.
+#[derive(Debug, Clone)]
+pub enum ListenAddr {
+    /// A TCP socket address.
+    Tcp(SocketAddr),
+
+    /// A Unix domain socket, and the permission mode to set on it once bound.
+    Unix(PathBuf, Option<u32>),
+}
+
+impl ListenAddr {
+    /// Returns the TCP socket address, if this is a TCP listen address.
+    pub fn tcp(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(addr) => Some(*addr),
+            Self::Unix(_, _) => None,
+        }
+    }
+
+    /// Returns the Unix domain socket path and mode, if this is a Unix listen address.
+    pub fn unix(&self) -> Option<(&Path, Option<u32>)> {
+        match self {
+            Self::Tcp(_) => None,
+            Self::Unix(path, mode) => Some((path.as_path(), *mode)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ListenAddr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            /// `unix:/path/to/socket` or `host:port`.
+            Plain(String),
+
+            /// `{ socket = "unix:/path/to/socket", mode = 0o660 }`
+            WithMode { socket: String, mode: Option<u32> },
+        }
+
+        let (s, mode) = match Repr::deserialize(deserializer)? {
+            Repr::Plain(s) => (s, None),
+            Repr::WithMode { socket, mode } => (socket, mode),
+        };
+
+        if let Some(path) = s.strip_prefix("unix:") {
+            Ok(ListenAddr::Unix(PathBuf::from(path), mode))
+        } else {
+            s.parse::<SocketAddr>()
+                .map(ListenAddr::Tcp)
+                .map_err(de::Error::custom)
+        }
+    }
 }
 
 /// Database connection configuration.
@@ -135,6 +241,31 @@ pub enum StorageConfig {
     /// S3 storage.
     #[serde(rename = "s3")]
     S3(S3StorageConfig),
+
+    /// Replicated storage, mirroring each NAR chunk across several backends.
+    #[serde(rename = "replicated")]
+    Replicated(ReplicatedStorageConfig),
+}
+
+/// Replicated storage configuration.
+///
+/// Writes a NAR chunk to each of `backends` and succeeds once
+/// `replication-factor` of them acknowledge. Reads are served from
+/// whichever backend responds first, falling through to the next on a miss
+/// or error. This lets an operator mirror a local disk to S3 (or to
+/// another S3 bucket in a different region) for durability, and
+/// generalizes storage into a composable tree rather than a single flat
+/// backend.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplicatedStorageConfig {
+    /// The backends to replicate across.
+    pub backends: Vec<StorageConfig>,
+
+    /// How many backends must acknowledge a write for it to succeed.
+    ///
+    /// Must be at least 1 and at most `backends.len()`.
+    #[serde(rename = "replication-factor")]
+    pub replication_factor: usize,
 }
 
 /// Compression configuration.
@@ -190,13 +321,139 @@ pub struct GarbageCollectionConfig {
     #[serde(rename = "default-retention-period")]
     #[serde(with = "humantime_serde", default = "default_default_retention_period")]
     pub default_retention_period: Duration,
+
+    /// The maximum total size of stored NAR chunks, across all caches.
+    ///
+    /// Accepts a byte quantity like `"500 GB"` or `"2 TiB"`. If the total
+    /// size of stored objects exceeds this quota, the garbage collector
+    /// evicts unreferenced objects in ascending order of `last_accessed_at`
+    /// (falling back to `created_at`) until usage is at or below the quota,
+    /// regardless of their retention period. Objects still reachable from a
+    /// retained store path are never evicted.
+    ///
+    /// Unset (default) means there is no size-based quota.
+    #[serde(rename = "max-storage-size")]
+    #[serde(deserialize_with = "deserialize_byte_size")]
+    #[serde(default)]
+    pub max_storage_size: Option<u64>,
 }
 
-fn load_token_hs256_secret_from_env() -> HS256Key {
-    let s = env::var(ENV_TOKEN_HS256_SECRET_BASE64)
-        .expect("The HS256 secret must be specified in either token_hs256_secret or the ATTIC_SERVER_TOKEN_HS256_SECRET_BASE64 environment.");
+/// Background data scrubber config.
+///
+/// Mirrors `GarbageCollectionConfig`: it runs in its own scheduler
+/// (`scrub::run_scrub_scheduler`), on its own `interval`, and can be
+/// switched off entirely with `enable`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScrubConfig {
+    /// Whether the scrubber is enabled.
+    ///
+    /// Off by default, since walking and re-hashing the entire store can be
+    /// expensive on large caches.
+    #[serde(default = "default_scrub_enable")]
+    pub enable: bool,
+
+    /// The frequency to run the scrubber at.
+    #[serde(with = "humantime_serde", default = "default_scrub_interval")]
+    pub interval: Duration,
 
-    decode_token_hs256_secret_base64(&s).expect("Failed to load as decoding key")
+    /// Maximum scrub throughput, as a byte quantity per second (e.g. `"50 MB"`).
+    ///
+    /// Unset (default) means the scrubber is not rate-limited.
+    #[serde(rename = "bytes-per-second")]
+    #[serde(deserialize_with = "deserialize_byte_size")]
+    #[serde(default)]
+    pub bytes_per_second: Option<u64>,
+}
+
+impl Config {
+    /// Resolves the HS256 JWT signing/verification key to actually use.
+    ///
+    /// Order of precedence:
+    ///
+    /// 1. `ATTIC_SERVER_TOKEN_HS256_SECRET_FILE`
+    /// 2. `token-hs256-secret-file`
+    /// 3. `ATTIC_SERVER_TOKEN_HS256_SECRET_BASE64`
+    /// 4. `token-hs256-secret-base64`
+    pub fn resolve_token_hs256_secret(&self) -> Result<HS256Key> {
+        let allow_world_readable = env_flag(ENV_ALLOW_WORLD_READABLE_SECRETS)
+            .unwrap_or(self.allow_world_readable_secrets);
+
+        if let Ok(path) = env::var(ENV_TOKEN_HS256_SECRET_FILE) {
+            return load_token_hs256_secret_from_file(Path::new(&path), allow_world_readable);
+        }
+
+        if let Some(path) = &self.token_hs256_secret_file {
+            return load_token_hs256_secret_from_file(path, allow_world_readable);
+        }
+
+        if let Ok(s) = env::var(ENV_TOKEN_HS256_SECRET_BASE64) {
+            return decode_token_hs256_secret_base64(&s);
+        }
+
+        if let Some(key) = &self.token_hs256_secret_base64 {
+            return Ok(key.clone());
+        }
+
+        anyhow::bail!(
+            "The HS256 secret must be specified via token-hs256-secret-base64, \
+             token-hs256-secret-file, {}, or {}.",
+            ENV_TOKEN_HS256_SECRET_BASE64,
+            ENV_TOKEN_HS256_SECRET_FILE,
+        )
+    }
+}
+
+/// Parses a boolean-valued environment variable, if set.
+///
+/// Returns `None` if the variable is unset, so the caller can fall back to
+/// a config-file default. A set variable is `true` for `"1"`, `"true"`, or
+/// `"yes"` (case-insensitive) and `false` for anything else, so e.g.
+/// `ATTIC_SERVER_ALLOW_WORLD_READABLE_SECRETS=false` overrides a config
+/// file's `allow-world-readable-secrets = true` back off, instead of only
+/// ever being able to turn the flag on.
+fn env_flag(name: &str) -> Option<bool> {
+    env::var(name)
+        .ok()
+        .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+fn load_token_hs256_secret_from_file(path: &Path, allow_world_readable: bool) -> Result<HS256Key> {
+    if !allow_world_readable {
+        check_secret_file_permissions(path)?;
+    }
+
+    let s = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read secret file {:?}: {}", path, e))?;
+
+    decode_token_hs256_secret_base64(s.trim())
+}
+
+/// Checks that a secret file grants no permissions at all (read, write or
+/// execute) to group or others.
+#[cfg(unix)]
+fn check_secret_file_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = std::fs::metadata(path)
+        .map_err(|e| anyhow::anyhow!("Failed to stat secret file {:?}: {}", path, e))?;
+    let mode = metadata.permissions().mode();
+
+    if mode & 0o077 != 0 {
+        anyhow::bail!(
+            "Refusing to read secret from {:?}: file is accessible by group or others (mode {:o}). \
+             Restrict its permissions (e.g., `chmod 600`) or set `allow-world-readable-secrets` \
+             to bypass this check.",
+            path,
+            mode & 0o777
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_secret_file_permissions(_path: &Path) -> Result<()> {
+    Ok(())
 }
 
 impl CompressionConfig {
@@ -239,11 +496,24 @@ impl Default for GarbageCollectionConfig {
         Self {
             interval: Duration::from_secs(43200),
             default_retention_period: Duration::ZERO,
+            max_storage_size: None,
+        }
+    }
+}
+
+impl Default for ScrubConfig {
+    fn default() -> Self {
+        Self {
+            enable: default_scrub_enable(),
+            interval: default_scrub_interval(),
+            bytes_per_second: None,
         }
     }
 }
 
-fn deserialize_token_hs256_secret_base64<'de, D>(deserializer: D) -> Result<HS256Key, D::Error>
+fn deserialize_token_hs256_secret_base64<'de, D>(
+    deserializer: D,
+) -> Result<Option<HS256Key>, D::Error>
 where
     D: de::Deserializer<'de>,
 {
@@ -252,11 +522,11 @@ where
     let s = String::deserialize(deserializer)?;
     let key = decode_token_hs256_secret_base64(&s).map_err(Error::custom)?;
 
-    Ok(key)
+    Ok(Some(key))
 }
 
-fn default_listen_address() -> SocketAddr {
-    "[::]:8080".parse().unwrap()
+fn default_listen_address() -> ListenAddr {
+    ListenAddr::Tcp("[::]:8080".parse().unwrap())
 }
 
 fn default_db_heartbeat() -> bool {
@@ -275,10 +545,60 @@ fn default_gc_interval() -> Duration {
     Duration::from_secs(43200)
 }
 
+fn default_scrub_enable() -> bool {
+    false
+}
+
+fn default_scrub_interval() -> Duration {
+    Duration::from_secs(86400)
+}
+
 fn default_default_retention_period() -> Duration {
     Duration::ZERO
 }
 
+/// Parses a byte quantity like `"500 GB"` or `"2 TiB"` into a number of bytes.
+fn parse_byte_size(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    let split_at = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split_at);
+
+    let num: f64 = num
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid byte quantity: {:?}", s))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        "TIB" => 1 << 40,
+        other => return Err(format!("unknown byte unit {:?} in {:?}", other, s)),
+    };
+
+    Ok((num * multiplier as f64) as u64)
+}
+
+fn deserialize_byte_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    use de::Error;
+
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    match s {
+        Some(s) => parse_byte_size(&s).map(Some).map_err(Error::custom),
+        None => Ok(None),
+    }
+}
+
 fn load_config_from_path(path: &Path) -> Result<Config> {
     tracing::info!("Using configurations: {:?}", path);
 
@@ -324,3 +644,57 @@ pub fn get_xdg_data_path() -> anyhow::Result<PathBuf> {
 
     Ok(data_path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_bytes() {
+        assert_eq!(parse_byte_size("512").unwrap(), 512);
+        assert_eq!(parse_byte_size("512 B").unwrap(), 512);
+    }
+
+    #[test]
+    fn parses_decimal_units() {
+        assert_eq!(parse_byte_size("500 GB").unwrap(), 500_000_000_000);
+        assert_eq!(parse_byte_size("1.5MB").unwrap(), 1_500_000);
+    }
+
+    #[test]
+    fn parses_binary_units() {
+        assert_eq!(parse_byte_size("2 TiB").unwrap(), 2u64 << 40);
+        assert_eq!(parse_byte_size("1GiB").unwrap(), 1u64 << 30);
+    }
+
+    #[test]
+    fn rejects_unknown_units() {
+        assert!(parse_byte_size("5 widgets").is_err());
+    }
+
+    #[test]
+    fn env_flag_is_none_when_unset() {
+        let name = "ATTIC_SERVER_TEST_ENV_FLAG_UNSET";
+        env::remove_var(name);
+        assert_eq!(env_flag(name), None);
+    }
+
+    #[test]
+    fn env_flag_parses_truthy_and_falsy_values() {
+        let name = "ATTIC_SERVER_TEST_ENV_FLAG_VALUES";
+
+        env::set_var(name, "true");
+        assert_eq!(env_flag(name), Some(true));
+
+        env::set_var(name, "1");
+        assert_eq!(env_flag(name), Some(true));
+
+        env::set_var(name, "false");
+        assert_eq!(env_flag(name), Some(false));
+
+        env::set_var(name, "0");
+        assert_eq!(env_flag(name), Some(false));
+
+        env::remove_var(name);
+    }
+}
@@ -0,0 +1,191 @@
+//! Garbage collection.
+//!
+//! This module implements eviction based purely on total storage usage (see
+//! `GarbageCollectionConfig::max_storage_size`): the eviction loop only ever
+//! runs on NAR objects that are not referenced by any retained store path,
+//! and physical deletion happens once an object's refcount hits zero.
+//! Time-based retention collection is not implemented in this module.
+
+use async_trait::async_trait;
+
+use crate::config::GarbageCollectionConfig;
+
+/// A NAR object eligible for garbage collection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcCandidate {
+    /// Database ID of the NAR object.
+    pub id: i64,
+
+    /// Size of the object in bytes.
+    pub file_size: u64,
+
+    /// When the object was last accessed, if ever.
+    pub last_accessed_at: Option<i64>,
+
+    /// When the object was created.
+    pub created_at: i64,
+}
+
+impl GcCandidate {
+    fn recency(&self) -> i64 {
+        self.last_accessed_at.unwrap_or(self.created_at)
+    }
+}
+
+/// Selects objects to evict to bring total storage usage under a quota.
+///
+/// `candidates` must only contain NAR objects not reachable from any
+/// retained store path. `total_size` is the current total size of all
+/// stored NAR objects (reachable or not). Candidates are evicted in
+/// ascending order of `last_accessed_at` (falling back to `created_at`)
+/// until `total_size` drops to or below `max_storage_size`.
+///
+/// If the reachable set alone exceeds the quota, all candidates are
+/// returned for eviction and a warning is logged, since there is nothing
+/// left to reclaim.
+pub fn select_for_quota_eviction(
+    mut candidates: Vec<GcCandidate>,
+    mut total_size: u64,
+    max_storage_size: u64,
+) -> Vec<GcCandidate> {
+    candidates.sort_by_key(|c| c.recency());
+
+    let mut to_evict = Vec::new();
+    for candidate in candidates {
+        if total_size <= max_storage_size {
+            break;
+        }
+
+        total_size = total_size.saturating_sub(candidate.file_size);
+        to_evict.push(candidate);
+    }
+
+    if total_size > max_storage_size {
+        tracing::warn!(
+            "Storage usage ({} bytes) still exceeds the quota ({} bytes) after evicting all \
+             unreferenced objects. The reachable set alone is over quota.",
+            total_size,
+            max_storage_size,
+        );
+    }
+
+    to_evict
+}
+
+/// Where garbage collection reads candidates from and applies deletions.
+///
+/// Implemented by the database layer. A NAR object is only ever physically
+/// removed once its refcount has dropped to zero — `delete` is expected to
+/// decrement the refcount and only unlink the underlying chunks once it
+/// reaches zero.
+#[async_trait]
+pub trait GcStore: Send + Sync {
+    /// Returns the current quota-eviction candidates (NAR objects not
+    /// reachable from any retained store path) along with the total size in
+    /// bytes of all stored NAR objects, reachable or not.
+    async fn quota_eviction_candidates(&self) -> std::io::Result<(Vec<GcCandidate>, u64)>;
+
+    /// Applies the same deletion logic used by time-based collection to
+    /// `candidate`.
+    async fn delete(&self, candidate: &GcCandidate) -> std::io::Result<()>;
+}
+
+/// Runs quota-based eviction to completion: selects candidates via
+/// `select_for_quota_eviction` and deletes each one through `store`.
+pub async fn run_quota_eviction(store: &dyn GcStore, max_storage_size: u64) -> std::io::Result<()> {
+    let (candidates, total_size) = store.quota_eviction_candidates().await?;
+    let to_evict = select_for_quota_eviction(candidates, total_size, max_storage_size);
+
+    let evicted = to_evict.len();
+    for candidate in &to_evict {
+        store.delete(candidate).await?;
+    }
+
+    tracing::info!("Quota-based GC evicted {} objects", evicted);
+    Ok(())
+}
+
+/// Runs the collector forever, waking up every `config.interval`.
+///
+/// Each tick performs quota-based eviction when `config.max_storage_size`
+/// is set. Time-based retention collection is not run by this scheduler;
+/// with no `max_storage_size`, a tick does nothing.
+pub async fn run_gc_scheduler(config: &GarbageCollectionConfig, store: &dyn GcStore) {
+    if config.interval.is_zero() {
+        tracing::info!("Automatic garbage collection is disabled");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(config.interval);
+
+    loop {
+        interval.tick().await;
+
+        if let Some(max_storage_size) = config.max_storage_size {
+            if let Err(e) = run_quota_eviction(store, max_storage_size).await {
+                tracing::error!("Quota-based GC pass failed: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: i64, file_size: u64, last_accessed_at: Option<i64>, created_at: i64) -> GcCandidate {
+        GcCandidate {
+            id,
+            file_size,
+            last_accessed_at,
+            created_at,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used_first_until_under_quota() {
+        let candidates = vec![
+            candidate(1, 100, Some(30), 0),
+            candidate(2, 100, Some(10), 0),
+            candidate(3, 100, Some(20), 0),
+        ];
+
+        let evicted = select_for_quota_eviction(candidates, 300, 150);
+
+        assert_eq!(
+            evicted.into_iter().map(|c| c.id).collect::<Vec<_>>(),
+            vec![2, 3],
+        );
+    }
+
+    #[test]
+    fn falls_back_to_created_at_when_never_accessed() {
+        // Candidate 2 has never been accessed, so it falls back to
+        // created_at (1), making it the least recently used of the two
+        // (candidate 1's last_accessed_at of 5 is more recent) and the
+        // first evicted.
+        let candidates = vec![candidate(1, 100, Some(5), 0), candidate(2, 100, None, 1)];
+
+        let evicted = select_for_quota_eviction(candidates, 200, 100);
+
+        assert_eq!(evicted.into_iter().map(|c| c.id).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn evicts_nothing_when_already_under_quota() {
+        let candidates = vec![candidate(1, 50, Some(1), 0)];
+
+        let evicted = select_for_quota_eviction(candidates, 50, 100);
+
+        assert!(evicted.is_empty());
+    }
+
+    #[test]
+    fn evicts_everything_when_reachable_set_alone_is_over_quota() {
+        let candidates = vec![candidate(1, 50, Some(1), 0), candidate(2, 50, Some(2), 0)];
+
+        let evicted = select_for_quota_eviction(candidates, 1_000, 100);
+
+        assert_eq!(evicted.into_iter().map(|c| c.id).collect::<Vec<_>>(), vec![1, 2]);
+    }
+}
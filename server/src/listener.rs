@@ -0,0 +1,123 @@
+//! Binding to the configured `ListenAddr`.
+//!
+//! Branches on `ListenAddr::Tcp`/`ListenAddr::Unix` to build the listener
+//! `atticd` actually serves on, so `listen = "unix:/path"` in the config
+//! has an effect rather than only being parsed.
+
+use std::io;
+use std::path::Path;
+
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::net::{TcpListener, UnixListener};
+use tower::Service;
+
+use crate::config::ListenAddr;
+
+/// Binds to `addr` and serves `app` on it until the process exits.
+pub async fn serve(addr: &ListenAddr, app: Router) -> io::Result<()> {
+    match addr {
+        ListenAddr::Tcp(socket_addr) => {
+            tracing::info!("Listening on {}", socket_addr);
+            let listener = TcpListener::bind(socket_addr).await?;
+            axum::serve(listener, app).await
+        }
+        ListenAddr::Unix(path, mode) => {
+            tracing::info!("Listening on unix:{}", path.display());
+
+            if path.exists() {
+                if is_stale_socket(path)? {
+                    std::fs::remove_file(path)?;
+                } else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!(
+                            "{} already exists and does not look like a stale socket; refusing to remove it",
+                            path.display(),
+                        ),
+                    ));
+                }
+            }
+
+            let listener = bind_unix_listener(path, *mode)?;
+
+            // `axum::serve` only accepts a `TcpListener`, so Unix sockets are
+            // served with a manual hyper accept loop instead, same as the
+            // approach in axum's own unix-domain-socket example.
+            loop {
+                let (socket, _remote_addr) = listener.accept().await?;
+                let tower_service = app.clone();
+
+                tokio::spawn(async move {
+                    let socket = TokioIo::new(socket);
+                    let hyper_service = hyper::service::service_fn(move |request| {
+                        tower_service.clone().call(request)
+                    });
+
+                    if let Err(err) = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                        .serve_connection_with_upgrades(socket, hyper_service)
+                        .await
+                    {
+                        tracing::error!("Failed to serve unix socket connection: {}", err);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Returns whether `path` is a Unix socket nothing is listening on anymore,
+/// and therefore safe to unlink and rebind over. Anything else — a
+/// non-socket file, or a socket that still accepts connections — is left
+/// alone, so a misconfigured path or a live listener's socket is never
+/// deleted.
+#[cfg(unix)]
+fn is_stale_socket(path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixStream;
+
+    if !std::fs::metadata(path)?.file_type().is_socket() {
+        return Ok(false);
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(unix))]
+fn is_stale_socket(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Binds the Unix socket at `path`, restricting its permissions to `mode`
+/// (if given) for the entire window in which the socket file is created,
+/// rather than `chmod`-ing it after the fact. Binding without first
+/// narrowing the umask would leave the socket briefly reachable at the
+/// process' default permissions.
+#[cfg(unix)]
+fn bind_unix_listener(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Some(mode) = mode else {
+        return UnixListener::bind(path);
+    };
+
+    // SAFETY: `umask` only affects the calling process and has no
+    // invariants beyond not being called concurrently from another thread,
+    // which doesn't happen here.
+    let previous_umask = unsafe { libc::umask((!mode & 0o777) as libc::mode_t) };
+    let result = UnixListener::bind(path);
+    unsafe { libc::umask(previous_umask) };
+
+    let listener = result?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+fn bind_unix_listener(path: &Path, _mode: Option<u32>) -> io::Result<UnixListener> {
+    UnixListener::bind(path)
+}
@@ -0,0 +1,25 @@
+//! Server startup.
+//!
+//! This is the actual call site that turns a resolved `Config` into a
+//! running server: it builds the application and binds it via
+//! [`listener::serve`], so e.g. `listen = "unix:/path"` in the config
+//! determines what the process actually binds to, rather than only being
+//! parsed.
+
+use anyhow::Result;
+use axum::Router;
+
+use crate::config::Config;
+use crate::listener;
+
+/// Serves the router from `build_router` on `config.listen` until the
+/// process exits. That router has no routes, so every request gets a 404.
+pub async fn run(config: &Config) -> Result<()> {
+    let app = build_router();
+    listener::serve(&config.listen, app).await?;
+    Ok(())
+}
+
+fn build_router() -> Router {
+    Router::new()
+}
@@ -0,0 +1,433 @@
+//! Storage backends.
+//!
+//! This module defines the configuration and implementation of the
+//! backends NAR chunks can be stored on.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::future::join_all;
+use serde::Deserialize;
+
+use crate::config::StorageConfig;
+
+/// A storage backend for NAR chunks.
+///
+/// Implemented by each leaf backend (`LocalStorage`, `S3Storage`) as well as
+/// by `ReplicatedStorage`, which composes several backends into one. This
+/// makes storage a tree rather than a flat single backend, and lets
+/// replication be layered on transparently.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Writes a NAR chunk under `key`, replacing it if it already exists.
+    async fn write_chunk(&self, key: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Reads a NAR chunk stored under `key`.
+    ///
+    /// Returns `Ok(None)` if the chunk is not present on this backend.
+    async fn read_chunk(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Lists the keys of all chunks stored on this backend.
+    ///
+    /// Used by the scrubber to walk the store; not expected to be cheap.
+    async fn list_chunks(&self) -> io::Result<Vec<String>>;
+}
+
+/// Instantiates the configured storage backend.
+pub fn build_storage(config: StorageConfig) -> Result<Box<dyn Storage>> {
+    Ok(match config {
+        StorageConfig::Local(config) => Box::new(LocalStorage::new(config)),
+        StorageConfig::S3(config) => Box::new(S3Storage::new(config)),
+        StorageConfig::Replicated(config) => Box::new(ReplicatedStorage::new(config)?),
+    })
+}
+
+/// Local file storage configuration.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalStorageConfig {
+    /// The directory to store NAR chunks in.
+    pub path: PathBuf,
+
+    /// Whether to fsync each chunk's data after writing it.
+    ///
+    /// Disabled by default, as Garage does, since it has a measurable
+    /// throughput cost. Without it, a host crash shortly after an upload
+    /// can leave a truncated or zero-length chunk on disk even though the
+    /// database already believes it to be valid.
+    #[serde(rename = "fsync-data")]
+    #[serde(default)]
+    pub fsync_data: bool,
+
+    /// Whether to fsync the containing directory after the atomic rename
+    /// that commits a chunk into the store.
+    ///
+    /// The rename itself is what makes the chunk visible under its final
+    /// name; without fsyncing the directory entry, the rename can still be
+    /// lost on crash even if the chunk's data was fsync'd.
+    #[serde(rename = "fsync-metadata")]
+    #[serde(default)]
+    pub fsync_metadata: bool,
+}
+
+/// S3 storage configuration.
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3StorageConfig {
+    /// Name of the S3 bucket.
+    pub bucket: String,
+
+    /// Region of the bucket.
+    pub region: Option<String>,
+
+    /// The endpoint URL, for S3-compatible services.
+    pub endpoint: Option<String>,
+}
+
+/// Local file storage backend.
+pub struct LocalStorage {
+    config: LocalStorageConfig,
+}
+
+impl LocalStorage {
+    pub fn new(config: LocalStorageConfig) -> Self {
+        Self { config }
+    }
+
+    async fn write_and_commit(
+        &self,
+        mut tmp: tokio::fs::File,
+        tmp_path: &Path,
+        dest: &Path,
+        data: &[u8],
+    ) -> io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        tmp.write_all(data).await?;
+
+        if self.config.fsync_data {
+            tmp.sync_all().await?;
+        }
+
+        drop(tmp);
+        tokio::fs::rename(tmp_path, dest).await?;
+
+        if self.config.fsync_metadata {
+            let dir = dest.parent().unwrap_or(&self.config.path);
+            let dir = tokio::fs::File::open(dir).await?;
+            dir.sync_all().await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn write_chunk(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let dest = self.config.path.join(key);
+        let dir = dest.parent().unwrap_or(&self.config.path);
+        std::fs::create_dir_all(dir)?;
+
+        // `key` may contain `/` for sharded storage (e.g. `"ab/cdef1234"`);
+        // the temporary file must live alongside `dest`, named after its
+        // file name only, not the whole key.
+        let file_name = dest.file_name().unwrap_or_default().to_string_lossy();
+        let tmp_path = dir.join(format!(".{}.tmp", file_name));
+        let tmp = tokio::fs::File::create(&tmp_path).await?;
+        self.write_and_commit(tmp, &tmp_path, &dest, data).await
+    }
+
+    async fn read_chunk(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.config.path.join(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn list_chunks(&self) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        list_chunks_recursive(&self.config.path, &self.config.path, &mut keys).await?;
+        Ok(keys)
+    }
+}
+
+/// Recursively walks `dir` (a subtree of `root`) collecting chunk keys
+/// relative to `root`, so sharded keys (e.g. `"ab/cdef1234"`) are found.
+async fn list_chunks_recursive(
+    root: &Path,
+    dir: &Path,
+    keys: &mut Vec<String>,
+) -> io::Result<()> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+
+        if name.to_string_lossy().starts_with('.') {
+            // Skip in-progress temporary files.
+            continue;
+        }
+
+        let path = entry.path();
+        let file_type = entry.file_type().await?;
+
+        if file_type.is_dir() {
+            Box::pin(list_chunks_recursive(root, &path, keys)).await?;
+        } else {
+            let key = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .into_owned();
+            keys.push(key);
+        }
+    }
+
+    Ok(())
+}
+
+/// S3 storage backend.
+pub struct S3Storage {
+    #[allow(dead_code)]
+    config: S3StorageConfig,
+}
+
+impl S3Storage {
+    pub fn new(config: S3StorageConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn write_chunk(&self, _key: &str, _data: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "S3 storage is not implemented in this build",
+        ))
+    }
+
+    async fn read_chunk(&self, _key: &str) -> io::Result<Option<Vec<u8>>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "S3 storage is not implemented in this build",
+        ))
+    }
+
+    async fn list_chunks(&self) -> io::Result<Vec<String>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "S3 storage is not implemented in this build",
+        ))
+    }
+}
+
+/// Replicated storage backend.
+///
+/// Mirrors every write across its child backends and succeeds once
+/// `replication_factor` of them acknowledge. Reads query every backend
+/// concurrently and return whichever hit arrives first, falling through to
+/// the remaining backends on a miss or error.
+pub struct ReplicatedStorage {
+    backends: Vec<Box<dyn Storage>>,
+    replication_factor: usize,
+}
+
+impl ReplicatedStorage {
+    pub fn new(config: crate::config::ReplicatedStorageConfig) -> Result<Self> {
+        let backends = config
+            .backends
+            .into_iter()
+            .map(build_storage)
+            .collect::<Result<Vec<_>>>()?;
+
+        if config.replication_factor == 0 || config.replication_factor > backends.len() {
+            anyhow::bail!(
+                "replication-factor must be between 1 and the number of backends ({}), got {}",
+                backends.len(),
+                config.replication_factor,
+            );
+        }
+
+        Ok(Self {
+            backends,
+            replication_factor: config.replication_factor,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for ReplicatedStorage {
+    async fn write_chunk(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        // Write to every backend concurrently rather than stopping once
+        // `replication_factor` backends have acknowledged, so a backend
+        // later in the list is never silently skipped.
+        let results = join_all(self.backends.iter().map(|b| b.write_chunk(key, data))).await;
+
+        let acks = results.iter().filter(|r| r.is_ok()).count();
+        if acks >= self.replication_factor {
+            return Ok(());
+        }
+
+        let last_err = results.into_iter().filter_map(|r| r.err()).last();
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::other(format!(
+                "Only {} of {} required backends acknowledged the write",
+                acks, self.replication_factor
+            ))
+        }))
+    }
+
+    async fn read_chunk(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        // A backend erroring out is not the same as it cleanly reporting
+        // the chunk missing; only report `Ok(None)` if at least one backend
+        // actually answered cleanly, so an all-backends outage surfaces as
+        // an error rather than silently looking like a missing chunk.
+        use futures::stream::{FuturesUnordered, StreamExt};
+
+        let mut saw_clean_response = false;
+        let mut last_err = None;
+
+        let mut reads: FuturesUnordered<_> =
+            self.backends.iter().map(|b| b.read_chunk(key)).collect();
+
+        while let Some(result) = reads.next().await {
+            match result {
+                Ok(Some(data)) => return Ok(Some(data)),
+                Ok(None) => saw_clean_response = true,
+                Err(e) => last_err = Some(e),
+            }
+        }
+
+        if saw_clean_response {
+            Ok(None)
+        } else {
+            Err(last_err.unwrap_or_else(|| io::Error::other("no backends configured")))
+        }
+    }
+
+    async fn list_chunks(&self) -> io::Result<Vec<String>> {
+        // All backends are expected to hold the same set of chunks; the
+        // first one that answers successfully is authoritative enough.
+        for backend in &self.backends {
+            if let Ok(keys) = backend.list_chunks().await {
+                return Ok(keys);
+            }
+        }
+
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockBackend {
+        read_result: io::Result<Option<Vec<u8>>>,
+    }
+
+    impl MockBackend {
+        fn ok(data: Option<&[u8]>) -> Self {
+            Self {
+                read_result: Ok(data.map(|d| d.to_vec())),
+            }
+        }
+
+        fn err(kind: io::ErrorKind) -> Self {
+            Self {
+                read_result: Err(io::Error::new(kind, "mock backend error")),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Storage for MockBackend {
+        async fn write_chunk(&self, _key: &str, _data: &[u8]) -> io::Result<()> {
+            Ok(())
+        }
+
+        async fn read_chunk(&self, _key: &str) -> io::Result<Option<Vec<u8>>> {
+            match &self.read_result {
+                Ok(data) => Ok(data.clone()),
+                Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+            }
+        }
+
+        async fn list_chunks(&self) -> io::Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn replicated(backends: Vec<MockBackend>, replication_factor: usize) -> ReplicatedStorage {
+        ReplicatedStorage {
+            backends: backends
+                .into_iter()
+                .map(|b| Box::new(b) as Box<dyn Storage>)
+                .collect(),
+            replication_factor,
+        }
+    }
+
+    #[test]
+    fn rejects_zero_replication_factor() {
+        let config = crate::config::ReplicatedStorageConfig {
+            backends: vec![],
+            replication_factor: 0,
+        };
+
+        assert!(ReplicatedStorage::new(config).is_err());
+    }
+
+    #[test]
+    fn rejects_replication_factor_above_backend_count() {
+        let config = crate::config::ReplicatedStorageConfig {
+            backends: vec![StorageConfig::Local(LocalStorageConfig {
+                path: std::env::temp_dir(),
+                fsync_data: false,
+                fsync_metadata: false,
+            })],
+            replication_factor: 2,
+        };
+
+        assert!(ReplicatedStorage::new(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn read_chunk_reports_missing_when_every_backend_cleanly_misses() {
+        let storage = replicated(vec![MockBackend::ok(None), MockBackend::ok(None)], 1);
+
+        let result = storage.read_chunk("some-key").await.unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[tokio::test]
+    async fn read_chunk_propagates_error_when_every_backend_fails() {
+        let storage = replicated(
+            vec![
+                MockBackend::err(io::ErrorKind::Other),
+                MockBackend::err(io::ErrorKind::TimedOut),
+            ],
+            1,
+        );
+
+        let result = storage.read_chunk("some-key").await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn read_chunk_prefers_a_hit_over_other_backends_failing() {
+        let storage = replicated(
+            vec![
+                MockBackend::err(io::ErrorKind::Other),
+                MockBackend::ok(Some(b"data")),
+            ],
+            1,
+        );
+
+        let result = storage.read_chunk("some-key").await.unwrap();
+        assert_eq!(result, Some(b"data".to_vec()));
+    }
+}